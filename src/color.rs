@@ -0,0 +1,14 @@
+//! Highlighting of out-of-range values in human-readable output.
+
+use std::fmt;
+
+/// Write `text` to `f`, in red if `alarm` is set. Color is only used if
+/// the value is being formatted with the alternate flag (`{:#}`), which
+/// our `Display` implementations take as a request for ANSI color.
+pub fn write_flagged(f: &mut fmt::Formatter, text: &str, alarm: bool) -> fmt::Result {
+	if alarm && f.alternate() {
+		write!(f, "\x1b[31m{}\x1b[0m", text)
+	} else {
+		f.write_str(text)
+	}
+}
@@ -0,0 +1,67 @@
+//! Support for running as a long-lived systemd service.
+
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::io::Write;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Send a state update such as "READY=1" to the service manager, using
+/// the sd_notify protocol. This does nothing if we weren't started by
+/// systemd with `Type=notify`.
+pub fn notify(state: &str) -> io::Result<()> {
+	let path = match env::var_os("NOTIFY_SOCKET") {
+		Some(path) => path,
+		None => return Ok(()),
+	};
+	let sock = try!(UnixDatagram::unbound());
+	// A leading '@' names a socket in the abstract namespace, which
+	// has no file on disk.
+	let path = path.to_string_lossy().into_owned();
+	if path.starts_with('@') {
+		let addr = try!(SocketAddr::from_abstract_name(path[1..].as_bytes()));
+		try!(sock.send_to_addr(state.as_bytes(), &addr));
+	} else {
+		try!(sock.send_to(state.as_bytes(), path));
+	}
+	Ok(())
+}
+
+/// Returns true if the service manager expects us to send
+/// "WATCHDOG=1" periodically.
+pub fn watchdog_enabled() -> bool {
+	if env::var_os("WATCHDOG_USEC").is_none() {
+		return false;
+	}
+	// The variables are inherited by any children the service starts,
+	// so they only apply to us if they name our PID.
+	match env::var("WATCHDOG_PID") {
+		Ok(pid) => pid.parse() == Ok(process::id()),
+		Err(_) => true,
+	}
+}
+
+/// A file holding our process ID, which is removed again when this is
+/// dropped.
+pub struct PidFile {
+	path: PathBuf,
+}
+
+impl PidFile {
+	/// Write our process ID to the file at `path`.
+	pub fn create<P: AsRef<Path>>(path: P) -> io::Result<PidFile> {
+		let path = path.as_ref().to_path_buf();
+		let mut file = try!(File::create(&path));
+		try!(writeln!(file, "{}", process::id()));
+		Ok(PidFile { path: path })
+	}
+}
+
+impl Drop for PidFile {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.path);
+	}
+}
@@ -0,0 +1,149 @@
+//! Monitoring of the host board's CPU load, memory, and temperature,
+//! read from /proc and sysfs.
+
+use color;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use units::Celsius;
+
+/// SoC temperature above which boards typically start throttling their
+/// CPU clocks.
+pub const THROTTLE_TEMP: Celsius = Celsius(80.0);
+
+/// Structure to hold one report on the host's health.
+#[derive(Debug)]
+pub struct HostStatus {
+	/// Fraction of time each CPU core was busy since the last report
+	pub cpu_load: Vec<f32>,
+	/// Memory in use, in kibibytes
+	pub mem_used: u64,
+	/// Total memory, in kibibytes
+	pub mem_total: u64,
+	/// Hottest thermal zone, if the board has any
+	pub temp: Option<Celsius>,
+}
+
+impl fmt::Display for HostStatus {
+	/// Show the status in aligned columns. Busy cores and a temperature
+	/// high enough to throttle are shown in red when formatted with
+	/// `{:#}`.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		try!(f.write_str("cpu"));
+		for load in &self.cpu_load {
+			try!(color::write_flagged(f, &format!(" {:3.0}%", load * 100.0), *load >= 0.9));
+		}
+		try!(write!(f, "  mem {:4}/{:4} MiB", self.mem_used / 1024, self.mem_total / 1024));
+		if let Some(temp) = self.temp {
+			try!(f.write_str("  temp"));
+			try!(color::write_flagged(f, &format!(" {:5.1}", temp), temp > THROTTLE_TEMP));
+		}
+		Ok(())
+	}
+}
+
+/// Tracks CPU time counters between reports, since load can only be
+/// computed from the change in those counters.
+pub struct HostMonitor {
+	prev: Vec<(u64, u64)>,
+}
+
+/// Read the busy and total jiffies for each core from /proc/stat.
+fn read_cpu_times() -> io::Result<Vec<(u64, u64)>> {
+	let stat = BufReader::new(try!(File::open("/proc/stat")));
+	let mut times = Vec::new();
+	for line in stat.lines() {
+		let line = try!(line);
+		// The aggregate line is "cpu ...", while per-core lines are
+		// "cpu0 ...", "cpu1 ...", and so on.
+		if !line.starts_with("cpu") || line.starts_with("cpu ") {
+			continue;
+		}
+		let fields: Vec<u64> = line.split_whitespace().skip(1)
+			.filter_map(|f| f.parse().ok())
+			.collect();
+		// The fourth and fifth fields are idle and iowait time. Only
+		// the first eight fields add up to the total, since the guest
+		// times after them are already counted in user and nice.
+		let total: u64 = fields.iter().take(8).sum();
+		let idle = fields.iter().skip(3).take(2).sum::<u64>();
+		times.push((total - idle, total));
+	}
+	Ok(times)
+}
+
+/// Read used and total memory, in kibibytes, from /proc/meminfo.
+fn read_memory() -> io::Result<(u64, u64)> {
+	let meminfo = BufReader::new(try!(File::open("/proc/meminfo")));
+	let mut total: u64 = 0;
+	let mut available = 0;
+	for line in meminfo.lines() {
+		let line = try!(line);
+		let mut fields = line.split_whitespace();
+		let key = fields.next();
+		let value = fields.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+		match key {
+			Some("MemTotal:") => total = value,
+			Some("MemAvailable:") => available = value,
+			_ => {}
+		}
+	}
+	Ok((total.saturating_sub(available), total))
+}
+
+/// Read the hottest thermal zone.
+fn read_temp() -> Option<Celsius> {
+	let zones = match fs::read_dir("/sys/class/thermal") {
+		Ok(zones) => zones,
+		Err(_) => return None,
+	};
+	let mut hottest = None;
+	for zone in zones.filter_map(|zone| zone.ok()) {
+		let mut contents = String::new();
+		let read = File::open(zone.path().join("temp"))
+			.and_then(|mut f| f.read_to_string(&mut contents));
+		if read.is_err() {
+			continue;
+		}
+		// Zone temperatures are reported in millidegrees.
+		if let Ok(millidegrees) = contents.trim().parse::<i32>() {
+			let temp = Celsius(millidegrees as f32 / 1000.0);
+			if hottest.map_or(true, |hottest| temp > hottest) {
+				hottest = Some(temp);
+			}
+		}
+	}
+	hottest
+}
+
+impl HostMonitor {
+	/// Start monitoring, taking the initial CPU time counters.
+	pub fn new() -> io::Result<HostMonitor> {
+		Ok(HostMonitor { prev: try!(read_cpu_times()) })
+	}
+
+	/// Report the host's health. CPU load is averaged over the time
+	/// since the previous call (or since `new`).
+	pub fn sample(&mut self) -> io::Result<HostStatus> {
+		let times = try!(read_cpu_times());
+		let cpu_load = times.iter().zip(self.prev.iter())
+			.map(|(&(busy, total), &(prev_busy, prev_total))| {
+				if total > prev_total {
+					busy.saturating_sub(prev_busy) as f32 / (total - prev_total) as f32
+				} else {
+					0.0
+				}
+			})
+			.collect();
+		self.prev = times;
+
+		let (mem_used, mem_total) = try!(read_memory());
+		Ok(HostStatus {
+			cpu_load: cpu_load,
+			mem_used: mem_used,
+			mem_total: mem_total,
+			temp: read_temp(),
+		})
+	}
+}
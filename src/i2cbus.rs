@@ -0,0 +1,24 @@
+//! Information about I2C buses that isn't available through the
+//! i2c-dev interface itself.
+
+use byteorder::{BigEndian, ReadBytesExt};
+use std::fs::File;
+use std::path::Path;
+
+/// Look up the clock frequency, in Hz, that the bus at `dev` (such as
+/// `/dev/i2c-1`) is configured for.
+///
+/// Linux doesn't allow changing the bus speed from userspace: it is set
+/// in the device tree (on a Raspberry Pi, with
+/// `dtparam=i2c_arm_baudrate=400000` in config.txt). So the best we can
+/// do is report it. Returns `None` if the adapter wasn't configured
+/// through the device tree.
+pub fn clock_frequency(dev: &str) -> Option<u32> {
+	let name = match Path::new(dev).file_name().and_then(|name| name.to_str()) {
+		Some(name) => name,
+		None => return None,
+	};
+	let path = format!("/sys/class/i2c-adapter/{}/of_node/clock-frequency", name);
+	// Device tree properties are stored as big-endian cells.
+	File::open(path).and_then(|mut f| f.read_u32::<BigEndian>()).ok()
+}
@@ -0,0 +1,272 @@
+//! Attitude estimation using Mahony's nonlinear complementary filter,
+//! which integrates the gyro and uses the accelerometer's measurement
+//! of gravity to correct the resulting drift in roll and pitch.
+
+use std::fmt;
+use units::{Degrees, Gs, Radians, RadiansPerSecond};
+
+/// Orientation as Euler angles.
+#[derive(Debug)]
+pub struct Attitude {
+	/// Rotation about the X axis
+	pub roll: Radians,
+	/// Rotation about the Y axis. The sensor's Z axis points up, so
+	/// positive pitch is nose down.
+	pub pitch: Radians,
+	/// Rotation about the Z axis. Without a magnetometer this is only
+	/// relative to the heading at startup, and drifts.
+	pub yaw: Radians,
+}
+
+impl fmt::Display for Attitude {
+	/// Show the angles in aligned columns, in degrees.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "roll {:+6.1}  pitch {:+6.1}  yaw {:+6.1}",
+			Degrees::from(self.roll),
+			Degrees::from(self.pitch),
+			Degrees::from(self.yaw))
+	}
+}
+
+/// State of the filter.
+pub struct Mahony {
+	/// Orientation quaternion (w, x, y, z) rotating the body frame to
+	/// the earth frame
+	q: [f32; 4],
+	/// Integral of the correction error
+	integral: [RadiansPerSecond; 3],
+	/// Proportional gain on the accelerometer correction
+	pub kp: f32,
+	/// Integral gain on the accelerometer correction, which lets the
+	/// filter learn a constant gyro bias
+	pub ki: f32,
+	/// When set, the accelerometer correction is scaled down as the
+	/// measured acceleration strays from 1g, reaching zero once it is
+	/// this far away. Under hard acceleration the accelerometer no
+	/// longer points at gravity, so trusting it would tilt the estimate.
+	pub accel_rejection: Option<Gs>,
+	/// When set, the accelerometer correction is also scaled down as
+	/// the rotation rate rises, reaching zero at this rate. Fast
+	/// rotation means centripetal acceleration at the sensor, which the
+	/// accelerometer can't tell from gravity.
+	pub gyro_rejection: Option<RadiansPerSecond>,
+	/// How much the last update trusted the accelerometer, from 0 to 1
+	accel_weight: f32,
+	/// Seconds left during which the integral term is held, because of
+	/// a recent gyro saturation
+	integral_hold: f32,
+}
+
+/// How long to hold the integral term after the gyro saturates, in
+/// seconds.
+const SATURATION_HOLD: f32 = 1.0;
+
+impl Mahony {
+	/// Start with the body frame level and facing the way it is now.
+	pub fn new(kp: f32, ki: f32) -> Mahony {
+		Mahony {
+			q: [1.0, 0.0, 0.0, 0.0],
+			integral: [RadiansPerSecond(0.0); 3],
+			kp: kp,
+			ki: ki,
+			accel_rejection: None,
+			gyro_rejection: None,
+			accel_weight: 1.0,
+			integral_hold: 0.0,
+		}
+	}
+
+	/// Tell the filter that the gyro just saturated. The integral term
+	/// assumes the remaining error is a slowly-changing gyro bias, but
+	/// after a saturation the error is really the rotation the gyro
+	/// missed. So for a while we stop learning bias and leave the
+	/// proportional correction to pull the attitude back.
+	pub fn gyro_saturated(&mut self) {
+		self.integral_hold = SATURATION_HOLD;
+	}
+
+	/// Advance the estimate by `dt` seconds, given the rotation rate and
+	/// the acceleration measured over that time. Pass `None` for the
+	/// acceleration if the reading can't be trusted, for instance
+	/// because it clipped, and only the gyro will be used.
+	pub fn update(&mut self, gyro: [RadiansPerSecond; 3], accel: Option<[Gs; 3]>, dt: f32) {
+		let (q0, q1, q2, q3) = (self.q[0], self.q[1], self.q[2], self.q[3]);
+		let gyro = [gyro[0].0, gyro[1].0, gyro[2].0];
+		let mut rate = gyro;
+
+		// A zero acceleration gives no direction to correct towards,
+		// so it skips the correction just like free fall does.
+		let accel = match accel {
+			Some(accel) => [accel[0].0, accel[1].0, accel[2].0],
+			None => [0.0; 3],
+		};
+		let norm = (accel[0] * accel[0] + accel[1] * accel[1] + accel[2] * accel[2]).sqrt();
+		let rate_norm = (gyro[0] * gyro[0] + gyro[1] * gyro[1] + gyro[2] * gyro[2]).sqrt();
+		let weight = match self.accel_rejection {
+			Some(limit) => (1.0 - (norm - 1.0).abs() / limit.0).max(0.0),
+			None => 1.0,
+		} * match self.gyro_rejection {
+			Some(limit) => (1.0 - rate_norm / limit.0).max(0.0),
+			None => 1.0,
+		};
+		self.accel_weight = if norm > 0.0 { weight } else { 0.0 };
+		// In free fall there's no direction to correct towards.
+		if norm > 0.0 && weight > 0.0 {
+			let a = [accel[0] / norm, accel[1] / norm, accel[2] / norm];
+
+			// The direction our current estimate says gravity should
+			// be, in the body frame.
+			let v = [
+				2.0 * (q1 * q3 - q0 * q2),
+				2.0 * (q0 * q1 + q2 * q3),
+				q0 * q0 - q1 * q1 - q2 * q2 + q3 * q3,
+			];
+
+			// The cross product of measured and estimated gravity is
+			// the rotation that would bring them into line.
+			let e = [
+				(a[1] * v[2] - a[2] * v[1]) * weight,
+				(a[2] * v[0] - a[0] * v[2]) * weight,
+				(a[0] * v[1] - a[1] * v[0]) * weight,
+			];
+
+			for i in 0..3 {
+				if self.ki > 0.0 && self.integral_hold <= 0.0 {
+					self.integral[i].0 += self.ki * e[i] * dt;
+				}
+				rate[i] += self.kp * e[i];
+			}
+		}
+
+		// The integral is our estimate of gyro bias, which doesn't
+		// depend on whether this step's accelerometer reading was
+		// trusted, so always compensate for it.
+		for i in 0..3 {
+			rate[i] += self.integral[i].0;
+		}
+
+		self.integral_hold = (self.integral_hold - dt).max(0.0);
+
+		// Integrate the quaternion derivative, q' = q * (0, rate) / 2.
+		let (gx, gy, gz) = (rate[0] * 0.5 * dt, rate[1] * 0.5 * dt, rate[2] * 0.5 * dt);
+		let q = [
+			q0 - q1 * gx - q2 * gy - q3 * gz,
+			q1 + q0 * gx + q2 * gz - q3 * gy,
+			q2 + q0 * gy - q1 * gz + q3 * gx,
+			q3 + q0 * gz + q1 * gy - q2 * gx,
+		];
+		let norm = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+		self.q = [q[0] / norm, q[1] / norm, q[2] / norm, q[3] / norm];
+	}
+
+	/// How much the last update trusted the accelerometer, from 0 (not
+	/// at all) to 1 (fully). This is useful when tuning the rejection
+	/// thresholds.
+	pub fn accel_weight(&self) -> f32 {
+		self.accel_weight
+	}
+
+	/// The current orientation as Euler angles.
+	pub fn attitude(&self) -> Attitude {
+		let (q0, q1, q2, q3) = (self.q[0], self.q[1], self.q[2], self.q[3]);
+		// Clamp, because rounding can push the argument to asin just
+		// past 1 when pitched straight up or down.
+		let sin_pitch = (2.0 * (q0 * q2 - q3 * q1)).max(-1.0).min(1.0);
+		Attitude {
+			roll: Radians((2.0 * (q0 * q1 + q2 * q3)).atan2(1.0 - 2.0 * (q1 * q1 + q2 * q2))),
+			pitch: Radians(sin_pitch.asin()),
+			yaw: Radians((2.0 * (q0 * q3 + q1 * q2)).atan2(1.0 - 2.0 * (q2 * q2 + q3 * q3))),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn degrees(a: Radians) -> f32 {
+		Degrees::from(a).0
+	}
+
+	/// The accelerometer reading at rest with the given roll and pitch.
+	fn gravity(roll: f32, pitch: f32) -> [Gs; 3] {
+		let (roll, pitch) = (roll.to_radians(), pitch.to_radians());
+		[Gs(-pitch.sin()), Gs(roll.sin() * pitch.cos()), Gs(roll.cos() * pitch.cos())]
+	}
+
+	#[test]
+	fn level_and_still_stays_level() {
+		let mut ahrs = Mahony::new(1.0, 0.1);
+		for _ in 0..1000 {
+			ahrs.update([RadiansPerSecond(0.0); 3], Some(gravity(0.0, 0.0)), 0.01);
+		}
+		let attitude = ahrs.attitude();
+		assert!(degrees(attitude.roll).abs() < 1e-3);
+		assert!(degrees(attitude.pitch).abs() < 1e-3);
+		assert!(degrees(attitude.yaw).abs() < 1e-3);
+	}
+
+	#[test]
+	fn converges_to_accelerometer_tilt() {
+		let mut ahrs = Mahony::new(1.0, 0.0);
+		for _ in 0..2000 {
+			ahrs.update([RadiansPerSecond(0.0); 3], Some(gravity(30.0, -20.0)), 0.01);
+		}
+		let attitude = ahrs.attitude();
+		assert!((degrees(attitude.roll) - 30.0).abs() < 0.1, "roll {:?}", attitude.roll);
+		assert!((degrees(attitude.pitch) + 20.0).abs() < 0.1, "pitch {:?}", attitude.pitch);
+	}
+
+	#[test]
+	fn nose_up_is_negative_pitch() {
+		// Tilting the front of the board up puts some of gravity's
+		// reaction along +X.
+		let tilt = 20.0f32.to_radians();
+		let mut ahrs = Mahony::new(1.0, 0.0);
+		for _ in 0..2000 {
+			ahrs.update([RadiansPerSecond(0.0); 3], Some([Gs(tilt.sin()), Gs(0.0), Gs(tilt.cos())]), 0.01);
+		}
+		let pitch = degrees(ahrs.attitude().pitch);
+		assert!((pitch + 20.0).abs() < 0.1, "pitch {}", pitch);
+	}
+
+	#[test]
+	fn learned_bias_applies_without_accel() {
+		// With a constant roll-rate bias, the integral learns to cancel
+		// it, and should keep cancelling it when the accelerometer
+		// drops out.
+		let bias = [RadiansPerSecond(0.02), RadiansPerSecond(0.0), RadiansPerSecond(0.0)];
+		let mut ahrs = Mahony::new(1.0, 0.5);
+		for _ in 0..20000 {
+			ahrs.update(bias, Some(gravity(0.0, 0.0)), 0.01);
+		}
+		let before = degrees(ahrs.attitude().roll);
+		for _ in 0..100 {
+			ahrs.update(bias, None, 0.01);
+		}
+		let after = degrees(ahrs.attitude().roll);
+		assert!((after - before).abs() < 0.05, "roll drifted from {} to {}", before, after);
+	}
+
+	#[test]
+	fn saturation_holds_integral() {
+		let mut ahrs = Mahony::new(1.0, 0.5);
+		ahrs.gyro_saturated();
+		for _ in 0..50 {
+			ahrs.update([RadiansPerSecond(0.0); 3], Some(gravity(30.0, 0.0)), 0.01);
+		}
+		assert_eq!(ahrs.integral[0].0, 0.0);
+
+		// Once the hold runs out the integral learns again, and the hold
+		// doesn't linger from having counted down past zero.
+		for _ in 0..100 {
+			ahrs.update([RadiansPerSecond(0.0); 3], Some(gravity(30.0, 0.0)), 0.01);
+		}
+		assert_eq!(ahrs.integral_hold, 0.0);
+		assert!(ahrs.integral[0].0 != 0.0);
+		ahrs.gyro_saturated();
+		let integral = ahrs.integral[0].0;
+		ahrs.update([RadiansPerSecond(0.0); 3], Some(gravity(30.0, 0.0)), 0.01);
+		assert_eq!(ahrs.integral[0].0, integral);
+	}
+}
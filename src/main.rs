@@ -1,35 +1,77 @@
 #![deny(missing_docs)]
 
 //! This program reads measurements from an MPU-9150 inertial
-//! measurement unit attached via I2C.
+//! measurement unit attached via I2C, and from an MS4525DO airspeed
+//! sensor if one is present. Each sensor may be on its own bus.
 
 extern crate byteorder;
 extern crate i2cdev;
+extern crate libc;
 
-use byteorder::{BigEndian, ReadBytesExt};
 use i2cdev::core::*;
 use i2cdev::linux::*;
 use std::env;
 use std::error::Error;
+use std::fmt;
 use std::io;
-use std::time::Duration;
+use std::io::Write;
+use std::process;
+use std::time::{Duration, Instant};
 use std::thread::sleep;
+use units::*;
 
-/// Read a contiguous series of `buf.length` registers from the given
-/// I2C device `bus`, starting with `reg`.
-fn read_reg<E: Error>(bus: &mut I2CDevice<Error=E>, reg: u8, buf: &mut [u8]) -> Result<(), E> {
-	try!(bus.write(&[reg]));
-	bus.read(buf)
+mod color;
+mod daemon;
+mod host;
+mod i2cbus;
+mod mahony;
+mod monitor;
+mod ms4525;
+mod output;
+mod overheat;
+mod rdwr;
+mod signals;
+mod units;
+mod watchdog;
+
+/// A device whose registers we can read and write.
+pub trait RegisterBus {
+	/// Type of errors from talking to the device.
+	type Error: Error;
+
+	/// Write `data` to the device. The first byte selects the register
+	/// to start writing at.
+	fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+	/// Read a contiguous series of `buf.length` registers, starting
+	/// with `reg`.
+	fn read_reg(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+/// Any I2C device can read registers by writing the register number and
+/// then reading, but the bus is released between the two, so another
+/// master could slip in. Where possible, use `rdwr::Device` instead.
+impl<T: I2CDevice> RegisterBus for T {
+	type Error = T::Error;
+
+	fn write(&mut self, data: &[u8]) -> Result<(), T::Error> {
+		I2CDevice::write(self, data)
+	}
+
+	fn read_reg(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), T::Error> {
+		try!(I2CDevice::write(self, &[reg]));
+		self.read(buf)
+	}
 }
 
 /// Set up an MPU-9150's configuration registers.
-pub fn setup<E: Error + From<io::Error>>(bus: &mut I2CDevice<Error=E>) -> Result<(), E> {
+pub fn setup<E: Error + From<io::Error>>(bus: &mut RegisterBus<Error=E>) -> Result<(), E> {
 	// This sensor has a "WhoAmI" register that, when read, should
 	// always return 0x68. If we read that register and get a
 	// different value, then this isn't an MPU-family IMU and we
 	// shouldn't try to poke at it further.
 	let mut buf = [0u8; 1];
-	try!(read_reg(bus, 0x75, &mut buf));
+	try!(bus.read_reg(0x75, &mut buf));
 	if buf[0] != 0x68 {
 		return Err(io::Error::new(io::ErrorKind::NotFound, "MPU-9150 WhoAmI returned wrong value").into());
 	}
@@ -45,20 +87,103 @@ pub fn setup<E: Error + From<io::Error>>(bus: &mut I2CDevice<Error=E>) -> Result
 	bus.write(&[0x19, 199, 0x06, 0x00, 0x00])
 }
 
+/// Put an MPU-9150 into its low-power sleep mode. It stops sampling
+/// until `setup` is called again.
+pub fn shutdown<E: Error>(bus: &mut RegisterBus<Error=E>) -> Result<(), E> {
+	// Set the SLEEP bit in the power management register.
+	bus.write(&[0x6b, 0x40])
+}
+
+/// Full-scale range of the gyro, as configured by `setup`.
+pub const GYRO_FULL_SCALE: DegreesPerSecond = DegreesPerSecond(250.0);
+
+/// Highest temperature the MPU-9150 is rated to operate at.
+pub const MAX_TEMP: Celsius = Celsius(85.0);
+
 /// Structure to hold measurements in real units.
 #[derive(Debug)]
 pub struct MPUSample {
-	/// Acceleration X/Y/Z in g's
-	pub accel: [f32; 3],
-	/// Temperature in degrees Celsius
-	pub temp: f32,
-	/// Rotational velocity X/Y/Z in degrees/second
-	pub gyro: [f32; 3],
+	/// Acceleration X/Y/Z
+	pub accel: [Gs; 3],
+	/// Temperature
+	pub temp: Celsius,
+	/// Rotational velocity X/Y/Z
+	pub gyro: [RadiansPerSecond; 3],
+	/// True if any gyro axis read at the limit of its range, in which
+	/// case the true rotation rate was probably faster than reported
+	pub gyro_saturated: bool,
+	/// True if any accelerometer axis read at the limit of its range,
+	/// typically from an impact or heavy vibration
+	pub accel_clipped: bool,
+}
+
+impl fmt::Display for MPUSample {
+	/// Show the sample in aligned columns, with rates in degrees/second.
+	/// Axes that clipped or saturated, and a temperature beyond the
+	/// sensor's rating, are shown in red when formatted with `{:#}`.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		try!(f.write_str("accel"));
+		for a in &self.accel {
+			try!(color::write_flagged(f, &format!(" {:+6.3}", a), self.accel_clipped));
+		}
+		try!(f.write_str("  gyro"));
+		for r in &self.gyro {
+			try!(color::write_flagged(f, &format!(" {:+6.1}", DegreesPerSecond::from(*r)), self.gyro_saturated));
+		}
+		try!(f.write_str("  temp"));
+		color::write_flagged(f, &format!(" {:5.1}", self.temp), self.temp > MAX_TEMP)
+	}
+}
+
+/// Number of bytes in the block of measurement registers, which holds
+/// three accelerometer axes, the temperature, and three gyro axes, each
+/// as a 16-bit value.
+pub const SAMPLE_LEN: usize = (3 + 1 + 3) * 2;
+
+/// Read the big-endian 16-bit signed value at `buf[i..i + 2]`.
+fn be_i16(buf: &[u8], i: usize) -> i16 {
+	(((buf[i] as u16) << 8) | buf[i + 1] as u16) as i16
+}
+
+/// Returns true if a raw 16-bit reading is pinned at the end of its
+/// range.
+fn railed(raw: i16) -> bool {
+	raw == i16::max_value() || raw == i16::min_value()
+}
+
+/// Decode an `MPUSample` from the raw contents of the measurement
+/// registers, as read starting at ACCEL_XOUT_H (0x3b). Only the first
+/// `SAMPLE_LEN` bytes of `buf` are used.
+///
+/// # Panics
+///
+/// Panics if `buf` is shorter than `SAMPLE_LEN`.
+pub fn decode(buf: &[u8]) -> MPUSample {
+	assert!(buf.len() >= SAMPLE_LEN, "MPU-9150 sample needs {} bytes, got {}", SAMPLE_LEN, buf.len());
+	let accel = [be_i16(buf, 0), be_i16(buf, 2), be_i16(buf, 4)];
+	let gyro = [be_i16(buf, 8), be_i16(buf, 10), be_i16(buf, 12)];
+	MPUSample {
+		accel: [
+			Gs((accel[0] as f32) / 16384.0),
+			Gs((accel[1] as f32) / 16384.0),
+			Gs((accel[2] as f32) / 16384.0),
+		],
+		temp: Celsius((be_i16(buf, 6) as f32) / 340.0 + 35.0),
+		// The gyro reports in units of 1/131 degrees/second, but we
+		// only want to deal in radians from here on.
+		gyro: [
+			DegreesPerSecond((gyro[0] as f32) / 131.0).into(),
+			DegreesPerSecond((gyro[1] as f32) / 131.0).into(),
+			DegreesPerSecond((gyro[2] as f32) / 131.0).into(),
+		],
+		gyro_saturated: gyro.iter().any(|&raw| railed(raw)),
+		accel_clipped: accel.iter().any(|&raw| railed(raw)),
+	}
 }
 
 /// Read an `MPUSample` from the given I2C device, which must have been
 /// initialized first using `setup`.
-pub fn read_sample<E: Error + From<io::Error>>(bus: &mut I2CDevice<Error=E>) -> Result<MPUSample, E> {
+pub fn read_sample<E: Error>(bus: &mut RegisterBus<Error=E>) -> Result<MPUSample, E> {
 	// This sensor family places the measured values in a contiguous
 	// block of registers, which allows us to do a bulk read of all
 	// of them at once. And it's important to do the read in bulk,
@@ -67,43 +192,550 @@ pub fn read_sample<E: Error + From<io::Error>>(bus: &mut I2CDevice<Error=E>) ->
 	// mid-read. If we read them byte-at-a-time, we could get a
 	// high-order byte from an old sample and a low-order byte from
 	// a new sample, and wind up with nonsense numbers.
-	let mut buf = [0u8; (3 + 1 + 3) * 2];
-	try!(read_reg(bus, 0x3b, &mut buf));
-
-	// If read_i16 returns an error, it will be of type io::Error.
-	// However, we're supposed to return errors of the type
-	// associated with the I2CDevice implementation we're using. So
-	// above we constrained type E to have an implementation of the
-	// From trait, which the try! macro will use to convert
-	// io::Error to E as needed.
-	let mut rdr = io::Cursor::new(buf);
-	Ok(MPUSample {
-		accel: [
-			(try!(rdr.read_i16::<BigEndian>()) as f32) / 16384.0,
-			(try!(rdr.read_i16::<BigEndian>()) as f32) / 16384.0,
-			(try!(rdr.read_i16::<BigEndian>()) as f32) / 16384.0,
-		],
-		temp: (try!(rdr.read_i16::<BigEndian>()) as f32) / 340.0 + 35.0,
-		gyro: [
-			(try!(rdr.read_i16::<BigEndian>()) as f32) / 131.0,
-			(try!(rdr.read_i16::<BigEndian>()) as f32) / 131.0,
-			(try!(rdr.read_i16::<BigEndian>()) as f32) / 131.0,
-		],
-	})
+	let mut buf = [0u8; SAMPLE_LEN];
+	try!(bus.read_reg(0x3b, &mut buf));
+	Ok(decode(&buf))
+}
+
+/// Split a sensor location of the form `/dev/i2c-1` or `/dev/i2c-1:0x69`
+/// into a bus path and an I2C address, using `default_addr` if no
+/// address was given. The address may be given in hex or decimal.
+///
+/// # Panics
+///
+/// Panics if the address isn't a number, or is too big for a 7-bit I2C
+/// address.
+fn parse_location(location: &str, default_addr: u16) -> (String, u16) {
+	match location.rfind(':') {
+		Some(i) => {
+			let addr = &location[i + 1..];
+			let addr = if addr.starts_with("0x") || addr.starts_with("0X") {
+				u16::from_str_radix(&addr[2..], 16)
+			} else {
+				addr.parse()
+			};
+			let addr = addr.expect(&format!("bad I2C address in {}", location));
+			// Catch this here: the kernel would only reject it once we
+			// try to talk to the device, with a much less helpful error.
+			if addr > 0x7f {
+				panic!("I2C address in {} is above 0x7f", location);
+			}
+			(location[..i].to_string(), addr)
+		}
+		None => (location.to_string(), default_addr),
+	}
+}
+
+/// Tell the service manager about a state change. If that fails, say so
+/// but keep running: losing a readiness or watchdog message shouldn't
+/// crash the sensor loop.
+fn notify<W: Write>(out: &mut W, state: &str) {
+	if let Err(e) = daemon::notify(state) {
+		logged(writeln!(out, "warning: sending {} to the service manager failed: {}", state, e));
+	}
+}
+
+/// Say something on standard error, for when our output itself is the
+/// problem. If even that fails there's nobody left to tell.
+fn report(message: &str) {
+	let _ = writeln!(io::stderr(), "warning: {}", message);
+}
+
+/// Report a failure to write our output, but keep running: losing a log
+/// line, for example to a full disk, shouldn't crash the sensor loop.
+fn logged(result: io::Result<()>) {
+	if let Err(e) = result {
+		report(&format!("writing output failed: {}", e));
+	}
+}
+
+/// How many loop periods two samples can be apart before the gap is
+/// treated as an outlier.
+const MAX_GAP_PERIODS: u32 = 4;
+
+/// Returns the number of seconds to integrate the gyro over for a `gap`
+/// between samples, when they're meant to be `nominal` apart, along with
+/// whether the gap was clamped.
+///
+/// Instant is monotonic, so a clock step from NTP can't move it, but a
+/// stalled process or a suspended board can still leave a gap much
+/// longer than the loop period. Integrating the current gyro rate over
+/// time we didn't observe would throw the attitude off, so such a gap
+/// is treated as one ordinary step.
+fn integration_step(gap: Duration, nominal: Duration) -> (f32, bool) {
+	let (step, clamped) = if gap > nominal * MAX_GAP_PERIODS { (nominal, true) } else { (gap, false) };
+	(step.as_secs() as f32 + step.subsec_nanos() as f32 * 1e-9, clamped)
+}
+
+/// Run `f`, returning its result along with how long it took.
+fn timed<T, F: FnOnce() -> T>(f: F) -> (T, Duration) {
+	let start = Instant::now();
+	let result = f();
+	(result, start.elapsed())
+}
+
+/// Note the clock speed of the I2C bus at `dev`, since a bus left at
+/// the common 100kHz default takes four times as long per transaction.
+fn report_bus_speed<W: Write>(out: &mut W, dev: &str) {
+	if let Some(hz) = i2cbus::clock_frequency(dev) {
+		logged(writeln!(out, "{} runs at {}kHz", dev, hz / 1000));
+	}
 }
 
 fn main() {
-	let dev = env::args().nth(1)
-		.expect(&format!("Usage: {} /dev/i2c-?",
-			env::args().nth(0).unwrap_or("program".into())
-		));
-	let mut bus = LinuxI2CDevice::new(&dev, 0x68)
-		.expect(&format!("opening {} failed", &dev));
+	let mut dev = None;
+	let mut imu_location = None;
+	let mut airspeed_location = None;
+	let mut watchdog_path = None;
+	let mut daemon = false;
+	let mut pidfile_path = None;
+	let mut log_path = None;
+	let mut i2c_budget = None;
+	let usage = format!("Usage: {} [monitor] [--imu BUS[:ADDR]] [--airspeed BUS[:ADDR]] [--watchdog /dev/watchdog] [--daemon] [--pidfile FILE] [--log FILE] [--i2c-budget MICROSECONDS] [/dev/i2c-?]",
+		env::args().nth(0).unwrap_or("program".into())
+	);
+	let mut args = env::args().skip(1).peekable();
+	// Starting with `monitor` draws a full-screen view for bench testing
+	// instead of printing a line per sample.
+	let monitor = args.peek().map_or(false, |arg| arg == "monitor");
+	if monitor {
+		args.next();
+	}
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().expect(&format!("{} needs a value\n{}", arg, usage));
+		match &arg[..] {
+			"--imu" => imu_location = Some(value()),
+			"--airspeed" => airspeed_location = Some(value()),
+			"--watchdog" => watchdog_path = Some(value()),
+			"--daemon" => daemon = true,
+			"--pidfile" => pidfile_path = Some(value()),
+			"--log" => log_path = Some(value()),
+			"--i2c-budget" => i2c_budget = Some(Duration::from_micros(
+				value().parse().expect("--i2c-budget takes a number of microseconds")
+			)),
+			// Anything else that looks like an option is most likely a
+			// typo, which shouldn't be mistaken for a bus path.
+			_ if arg.starts_with("-") => panic!("unknown option {}\n{}", arg, usage),
+			_ => dev = Some(arg),
+		}
+	}
+
+	// The monitor takes over the terminal, so it makes no sense under a
+	// service manager or when writing to a log file.
+	if monitor && (daemon || log_path.is_some()) {
+		panic!("monitor can't be combined with --daemon or --log\n{}", usage);
+	}
+
+	// Each sensor can be given its own bus and address. Any sensor
+	// that isn't placed explicitly is looked for on the default bus.
+	let (imu_dev, imu_addr) = imu_location.or(dev.clone()).map(|location| parse_location(&location, 0x68))
+		.expect(&usage);
+	let pidfile = pidfile_path.map(|path| {
+		daemon::PidFile::create(&path)
+			.expect(&format!("writing {} failed", &path))
+	});
+	let logging = log_path.is_some();
+	let mut out = match log_path {
+		Some(path) => output::Output::log(&path)
+			.expect(&format!("opening {} failed", &path)),
+		None if monitor => output::Output::monitor(),
+		None => output::Output::stdout(),
+	};
+	let mut bus = rdwr::Device::open(&imu_dev, imu_addr)
+		.expect(&format!("opening {} failed", &imu_dev));
+	report_bus_speed(&mut out, &imu_dev);
 
 	setup(&mut bus).unwrap();
 
+	// Fixed-wing builds carry a pitot tube, but multirotors don't, so
+	// unless the sensor was placed explicitly, only report airspeed if
+	// it answers on the default bus while calibrating.
+	let mut airspeed = match airspeed_location {
+		Some(location) => {
+			let (dev, addr) = parse_location(&location, ms4525::ADDRESS);
+			let mut bus = LinuxI2CDevice::new(&dev, addr)
+				.expect(&format!("opening {} failed", &dev));
+			report_bus_speed(&mut out, &dev);
+			let cal = ms4525::calibrate(&mut bus, 50).unwrap();
+			Some((bus, cal))
+		}
+		None => dev.and_then(|dev| LinuxI2CDevice::new(&dev, ms4525::ADDRESS).ok()).and_then(|mut bus| {
+			match ms4525::calibrate(&mut bus, 50) {
+				Ok(cal) => Some((bus, cal)),
+				Err(_) => None,
+			}
+		}),
+	};
+
+	// Arm the watchdog last, so that slow sensor setup can't trip it.
+	let mut watchdog = watchdog_path.map(|path| {
+		watchdog::Watchdog::open(&path)
+			.expect(&format!("opening {} failed", &path))
+	});
+
+	// Checking on the host is comparatively slow, so only do it every
+	// few seconds rather than on every pass through the loop.
+	let mut host = host::HostMonitor::new().ok();
+	let host_interval = 25;
+	let mut passes = 0;
+
+	let mut imu_overheat = overheat::Overheat::new(MAX_TEMP, Celsius(5.0));
+	let mut host_overheat = overheat::Overheat::new(host::THROTTLE_TEMP, Celsius(5.0));
+
+	// Estimate attitude from the IMU, discounting the accelerometer
+	// when it's clearly measuring more than gravity: when its reading
+	// is far from 1g, or when we're spinning near the gyro's full-scale
+	// range. A small integral gain lets it learn the gyro's bias, which
+	// otherwise leaves a steady tilt error.
+	let mut ahrs = mahony::Mahony::new(1.0, 0.05);
+	ahrs.accel_rejection = Some(Gs(0.2));
+	ahrs.gyro_rejection = Some(GYRO_FULL_SCALE.into());
+	let mut last_sample = Instant::now();
+	let mut gyro_saturations = 0;
+	let mut accel_clips = 0;
+
+	// Log files get rotated and reopened on SIGHUP, but on a terminal
+	// SIGHUP means the session has gone away, so stop cleanly instead.
+	signals::install(logging);
+	if daemon {
+		notify(&mut out, "READY=1");
+	}
+
 	let delay = Duration::from_millis(200);
-	while let Ok(sample) = { sleep(delay); read_sample(&mut bus) } {
-		println!("{:?}", sample);
+	// By default, complain if a single sensor read eats more than a
+	// tenth of the loop period.
+	let i2c_budget = i2c_budget.unwrap_or(delay / 10);
+	while !signals::exit_requested() {
+		sleep(delay);
+		// Most signals arrive while we sleep. Stop before writing
+		// anything, in case it was a hangup and the terminal is gone.
+		if signals::exit_requested() {
+			break;
+		}
+		let (sample, elapsed) = timed(|| read_sample(&mut bus));
+		let sample = match sample {
+			Ok(sample) => sample,
+			Err(e) => {
+				logged(writeln!(out, "error: reading IMU failed: {}", e));
+				break;
+			}
+		};
+		if elapsed > i2c_budget {
+			logged(writeln!(out, "warning: IMU read took {:?}, over the {:?} budget", elapsed, i2c_budget));
+		}
+		if let Some(ref mut watchdog) = watchdog {
+			watchdog.pet().unwrap();
+		}
+		if daemon && daemon::watchdog_enabled() {
+			notify(&mut out, "WATCHDOG=1");
+		}
+		// If the log can't be reopened, say so and keep writing to the
+		// old file rather than stopping.
+		if signals::take_reopen_request() {
+			if let Err(e) = out.reopen() {
+				report(&format!("reopening the log failed: {}", e));
+			}
+		}
+		logged(out.show(&sample));
+		if let Some(monitor) = out.monitor_mut() {
+			monitor.imu(&sample);
+		}
+
+		let now = Instant::now();
+		let gap = now.duration_since(last_sample);
+		last_sample = now;
+		let (dt, clamped) = integration_step(gap, delay);
+		if clamped {
+			logged(writeln!(out, "warning: {:?} between samples, integrating as {:?}", gap, delay));
+		}
+		if sample.gyro_saturated {
+			gyro_saturations += 1;
+			logged(writeln!(out, "warning: gyro saturated ({} times so far), attitude may be off", gyro_saturations));
+			ahrs.gyro_saturated();
+		}
+		// A clipped reading doesn't point at gravity, so leave it out
+		// of the attitude correction. Frequent clipping points to
+		// impacts or a vibration problem worth looking into.
+		if sample.accel_clipped {
+			accel_clips += 1;
+			logged(writeln!(out, "warning: accelerometer clipped ({} times so far), check for vibration", accel_clips));
+		}
+		let accel = if sample.accel_clipped { None } else { Some(sample.accel) };
+		ahrs.update(sample.gyro, accel, dt);
+		let attitude = ahrs.attitude();
+		logged(out.show(&format!("{}  accel weight {:.2}", attitude, ahrs.accel_weight())));
+		if let Some(monitor) = out.monitor_mut() {
+			monitor.attitude(&attitude, ahrs.accel_weight());
+		}
+
+		match imu_overheat.update(sample.temp) {
+			Some(overheat::Transition::Overheated) =>
+				logged(writeln!(out, "warning: IMU is overheating, readings may be unreliable")),
+			Some(overheat::Transition::Recovered) =>
+				logged(writeln!(out, "IMU temperature back to normal")),
+			None => {}
+		}
+		if let Some((ref mut bus, ref cal)) = airspeed {
+			let (sample, elapsed) = timed(|| ms4525::read_sample(bus, cal));
+			match sample {
+				Ok(sample) => {
+					logged(out.show(&sample));
+					if let Some(monitor) = out.monitor_mut() {
+						monitor.airspeed(&sample);
+					}
+				}
+				Err(e) => logged(writeln!(out, "airspeed: {}", e)),
+			}
+			if elapsed > i2c_budget {
+				logged(writeln!(out, "warning: airspeed read took {:?}, over the {:?} budget", elapsed, i2c_budget));
+			}
+		}
+
+		passes += 1;
+		if passes % host_interval == 0 {
+			if let Some(ref mut host) = host {
+				match host.sample() {
+					Ok(status) => {
+						logged(out.show(&status));
+						if let Some(monitor) = out.monitor_mut() {
+							monitor.host(&status);
+						}
+						// A throttled CPU may not keep up with the
+						// sensors, so make this hard to miss.
+						match status.temp.and_then(|temp| host_overheat.update(temp)) {
+							Some(overheat::Transition::Overheated) =>
+								logged(writeln!(out, "warning: host is hot enough to throttle, loop timing may suffer")),
+							Some(overheat::Transition::Recovered) =>
+								logged(writeln!(out, "host temperature back to normal")),
+							None => {}
+						}
+					}
+					Err(e) => logged(writeln!(out, "host: {}", e)),
+				}
+			}
+		}
+		logged(out.redraw());
+	}
+
+	// If we stopped because of a read error rather than a signal,
+	// leave the watchdog armed so the board gets reset, and exit with
+	// an error so that a service manager knows to restart us.
+	if signals::exit_requested() {
+		// Disarm the watchdog before anything else, so that nothing
+		// going wrong below, such as failing to put the IMU to sleep or
+		// writing to a terminal that has hung up, can turn a clean exit
+		// into a reset.
+		if let Some(watchdog) = watchdog {
+			watchdog.close().unwrap();
+		}
+		if daemon {
+			notify(&mut out, "STOPPING=1");
+		}
+		if let Err(e) = shutdown(&mut bus) {
+			logged(writeln!(out, "warning: putting IMU to sleep failed: {}", e));
+		}
+		// After a hangup there may be no terminal left to write to, and
+		// that shouldn't turn a clean exit into a panic.
+		let _ = out.redraw();
+		let _ = out.flush();
+	} else {
+		logged(out.redraw());
+		logged(out.flush());
+		drop(pidfile);
+		process::exit(1);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn close(a: f32, b: f32) -> bool {
+		(a - b).abs() <= 1e-5 * b.abs().max(1.0)
+	}
+
+	/// A register block with every 16-bit value set to `raw`.
+	fn filled(raw: u16) -> [u8; SAMPLE_LEN] {
+		let mut buf = [0u8; SAMPLE_LEN];
+		for pair in buf.chunks_mut(2) {
+			pair[0] = (raw >> 8) as u8;
+			pair[1] = raw as u8;
+		}
+		buf
+	}
+
+	fn check_all(sample: &MPUSample, raw: i16) {
+		let raw = raw as f32;
+		for a in &sample.accel {
+			assert!(close(a.0, raw / 16384.0), "accel {:?} for raw {}", a, raw);
+		}
+		assert!(close(sample.temp.0, raw / 340.0 + 35.0), "temp {:?} for raw {}", sample.temp, raw);
+		for r in &sample.gyro {
+			assert!(close(r.0, (raw / 131.0).to_radians()), "gyro {:?} for raw {}", r, raw);
+		}
+	}
+
+	#[test]
+	fn decode_zero() {
+		let sample = decode(&[0; SAMPLE_LEN]);
+		check_all(&sample, 0);
+		assert_eq!(sample.temp, Celsius(35.0));
+		assert!(!sample.gyro_saturated);
+		assert!(!sample.accel_clipped);
+	}
+
+	#[test]
+	fn decode_max() {
+		let sample = decode(&filled(0x7fff));
+		check_all(&sample, i16::max_value());
+		assert!(close(sample.accel[0].0, 1.99994));
+		assert!(close(sample.temp.0, 131.373));
+		assert!(close(sample.gyro[0].0, 4.365594));
+	}
+
+	#[test]
+	fn decode_min() {
+		let sample = decode(&filled(0x8000));
+		check_all(&sample, i16::min_value());
+		assert_eq!(sample.accel[0], Gs(-2.0));
+		assert!(close(sample.temp.0, -61.376));
+		assert!(close(sample.gyro[0].0, -4.365727));
+	}
+
+	#[test]
+	fn decode_temperature_offset() {
+		// The temperature register reads 0 at 35C, with 340 counts per
+		// degree.
+		let mut buf = [0u8; SAMPLE_LEN];
+		assert_eq!(decode(&buf).temp, Celsius(35.0));
+		buf[6] = 0x01;
+		buf[7] = 0x54;
+		assert!(close(decode(&buf).temp.0, 36.0));
+		buf[6] = 0xfe;
+		buf[7] = 0xac;
+		assert!(close(decode(&buf).temp.0, 34.0));
+	}
+
+	#[test]
+	fn decode_is_big_endian() {
+		let mut buf = [0u8; SAMPLE_LEN];
+		buf[0] = 0x12;
+		buf[1] = 0x34;
+		buf[12] = 0xed;
+		buf[13] = 0xcc;
+		let sample = decode(&buf);
+		assert_eq!(sample.accel[0], Gs(0x1234 as f32 / 16384.0));
+		assert!(close(sample.gyro[2].0, (-0x1234 as f32 / 131.0).to_radians()));
+	}
+
+	#[test]
+	fn decode_ignores_trailing_bytes() {
+		let mut buf = [0xffu8; SAMPLE_LEN + 2];
+		for b in &mut buf[..SAMPLE_LEN] {
+			*b = 0;
+		}
+		check_all(&decode(&buf), 0);
+	}
+
+	#[test]
+	#[should_panic]
+	fn decode_short_buffer() {
+		decode(&[0; SAMPLE_LEN - 1]);
+	}
+
+	#[test]
+	fn flags_only_for_railed_values() {
+		// One count short of either rail isn't saturated.
+		assert!(!decode(&filled(0x7ffe)).gyro_saturated);
+		assert!(!decode(&filled(0x7ffe)).accel_clipped);
+		assert!(!decode(&filled(0x8001)).gyro_saturated);
+		assert!(!decode(&filled(0x8001)).accel_clipped);
+
+		// A railed gyro axis doesn't mark the accelerometer, or vice
+		// versa, and a railed temperature marks neither.
+		let mut buf = [0u8; SAMPLE_LEN];
+		buf[10] = 0x80;
+		let sample = decode(&buf);
+		assert!(sample.gyro_saturated);
+		assert!(!sample.accel_clipped);
+
+		let mut buf = [0u8; SAMPLE_LEN];
+		buf[4] = 0x7f;
+		buf[5] = 0xff;
+		let sample = decode(&buf);
+		assert!(sample.accel_clipped);
+		assert!(!sample.gyro_saturated);
+
+		let mut buf = [0u8; SAMPLE_LEN];
+		buf[6] = 0x7f;
+		buf[7] = 0xff;
+		let sample = decode(&buf);
+		assert!(!sample.accel_clipped);
+		assert!(!sample.gyro_saturated);
+	}
+
+	fn check_step(gap: Duration, seconds: f32, clamped: bool) {
+		let (step, was_clamped) = integration_step(gap, Duration::from_millis(200));
+		assert!(close(step, seconds), "{:?} integrated as {}", gap, step);
+		assert_eq!(was_clamped, clamped, "{:?}", gap);
+	}
+
+	#[test]
+	fn integration_step_at_threshold() {
+		check_step(Duration::from_millis(200), 0.2, false);
+		check_step(Duration::from_millis(200 * MAX_GAP_PERIODS as u64), 0.8, false);
+	}
+
+	#[test]
+	fn integration_step_clamps_long_gaps() {
+		check_step(Duration::from_millis(200 * MAX_GAP_PERIODS as u64) + Duration::from_micros(1), 0.2, true);
+		check_step(Duration::from_secs(3600), 0.2, true);
+	}
+
+	#[test]
+	fn location_without_address_uses_default() {
+		assert_eq!(parse_location("/dev/i2c-1", 0x68), ("/dev/i2c-1".to_string(), 0x68));
+	}
+
+	#[test]
+	fn location_address_in_hex_or_decimal() {
+		assert_eq!(parse_location("/dev/i2c-1:0x69", 0x68), ("/dev/i2c-1".to_string(), 0x69));
+		assert_eq!(parse_location("/dev/i2c-1:0X6A", 0x68), ("/dev/i2c-1".to_string(), 0x6a));
+		assert_eq!(parse_location("/dev/i2c-1:40", 0x68), ("/dev/i2c-1".to_string(), 40));
+	}
+
+	#[test]
+	fn location_splits_at_last_colon() {
+		assert_eq!(parse_location("/dev/i2c:bus:0x28", 0x68), ("/dev/i2c:bus".to_string(), 0x28));
+	}
+
+	#[test]
+	fn location_accepts_highest_address() {
+		assert_eq!(parse_location("/dev/i2c-1:0x7f", 0x68), ("/dev/i2c-1".to_string(), 0x7f));
+	}
+
+	#[test]
+	#[should_panic(expected = "above 0x7f")]
+	fn location_rejects_ten_bit_address() {
+		parse_location("/dev/i2c-1:0x1ff", 0x68);
+	}
+
+	#[test]
+	#[should_panic(expected = "bad I2C address")]
+	fn location_rejects_non_number() {
+		parse_location("/dev/i2c-1:imu", 0x68);
+	}
+
+	#[test]
+	#[should_panic(expected = "bad I2C address")]
+	fn location_rejects_empty_hex() {
+		parse_location("/dev/i2c-1:0x", 0x68);
+	}
+
+	#[test]
+	fn display_shows_units() {
+		let sample = decode(&[0u8; SAMPLE_LEN]);
+		assert_eq!(format!("{}", sample),
+			"accel +0.000 g +0.000 g +0.000 g  gyro   +0.0 deg/s   +0.0 deg/s   +0.0 deg/s  temp  35.0 C");
 	}
 }
@@ -0,0 +1,251 @@
+//! A full-screen view of the sensors for bench testing over SSH, redrawn
+//! in place on every pass instead of scrolling a line per sample.
+
+use host::HostStatus;
+use mahony::Attitude;
+use ms4525::AirspeedSample;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use units::{Degrees, DegreesPerSecond, Gs, Radians};
+use MPUSample;
+
+/// How many samples each sparkline spans.
+const HISTORY: usize = 60;
+
+/// How many of the most recent log lines to keep on screen.
+const LOG_LINES: usize = 8;
+
+/// Size of the artificial horizon, in characters.
+const HORIZON_WIDTH: usize = 41;
+const HORIZON_HEIGHT: usize = 11;
+
+/// How many degrees of pitch move the horizon by one row.
+const DEGREES_PER_ROW: Degrees = Degrees(5.0);
+
+/// Bar characters for sparklines, from lowest to highest.
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Everything the monitor shows, kept between redraws. Anything written
+/// to it with `Write` goes to the log pane, one entry per line.
+pub struct Monitor {
+	gyro: [VecDeque<f32>; 3],
+	accel: [VecDeque<f32>; 3],
+	imu: Option<String>,
+	attitude: Option<(Radians, Radians, String)>,
+	airspeed: Option<String>,
+	host: Option<String>,
+	log: VecDeque<String>,
+	partial: Vec<u8>,
+}
+
+/// Add `value` to `history`, dropping the oldest value once it's full.
+fn push(history: &mut VecDeque<f32>, value: f32) {
+	if history.len() == HISTORY {
+		history.pop_front();
+	}
+	history.push_back(value);
+}
+
+/// Draw `history` as a row of bars scaled between its own minimum and
+/// maximum, returning the bars along with that range.
+fn sparkline(history: &VecDeque<f32>) -> (String, f32, f32) {
+	let min = history.iter().cloned().fold(::std::f32::INFINITY, f32::min);
+	let max = history.iter().cloned().fold(::std::f32::NEG_INFINITY, f32::max);
+	let bars = history.iter().map(|&v| {
+		// A flat line has no range to scale by, so sit it in the middle.
+		let level = if max > min { (v - min) / (max - min) } else { 0.5 };
+		BARS[((level * (BARS.len() - 1) as f32).round() as usize).min(BARS.len() - 1)]
+	}).collect();
+	(bars, min, max)
+}
+
+/// Draw an artificial horizon for the given roll and pitch, as seen from
+/// behind the aircraft. Ground is filled in, and the middle
+/// of the view is marked with the aircraft's wings.
+///
+/// The angles follow `Attitude`'s signs, so positive pitch is nose down.
+fn horizon(roll: Radians, pitch: Radians) -> Vec<String> {
+	let (sin, cos) = roll.0.sin_cos();
+	// Nose down brings the horizon up, above the wings.
+	let offset = -Degrees::from(pitch).0 / DEGREES_PER_ROW.0;
+	let (cx, cy) = ((HORIZON_WIDTH / 2) as f32, (HORIZON_HEIGHT / 2) as f32);
+	(0..HORIZON_HEIGHT).map(|row| {
+		(0..HORIZON_WIDTH).map(|col| {
+			// Characters are about twice as tall as they are wide.
+			let x = (col as f32 - cx) / 2.0;
+			let y = cy - row as f32;
+			let wing = row == HORIZON_HEIGHT / 2 && (col as f32 - cx).abs() <= 3.0;
+			if wing {
+				if col as f32 == cx { 'o' } else { '-' }
+			} else if y * cos - x * sin < -offset {
+				':'
+			} else {
+				' '
+			}
+		}).collect()
+	}).collect()
+}
+
+impl Monitor {
+	/// Start with empty panes.
+	pub fn new() -> Monitor {
+		Monitor {
+			gyro: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+			accel: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+			imu: None,
+			attitude: None,
+			airspeed: None,
+			host: None,
+			log: VecDeque::new(),
+			partial: Vec::new(),
+		}
+	}
+
+	/// Record a new IMU sample.
+	pub fn imu(&mut self, sample: &MPUSample) {
+		for i in 0..3 {
+			push(&mut self.gyro[i], DegreesPerSecond::from(sample.gyro[i]).0);
+			push(&mut self.accel[i], sample.accel[i].0);
+		}
+		self.imu = Some(format!("{:#}", sample));
+	}
+
+	/// Record a new attitude estimate, along with how much it trusted
+	/// the accelerometer.
+	pub fn attitude(&mut self, attitude: &Attitude, accel_weight: f32) {
+		self.attitude = Some((
+			attitude.roll,
+			attitude.pitch,
+			format!("{}  accel weight {:.2}", attitude, accel_weight),
+		));
+	}
+
+	/// Record a new airspeed sample.
+	pub fn airspeed(&mut self, sample: &AirspeedSample) {
+		self.airspeed = Some(format!("{:#}", sample));
+	}
+
+	/// Record a new report on the host's health.
+	pub fn host(&mut self, status: &HostStatus) {
+		self.host = Some(format!("{:#}", status));
+	}
+
+	/// Redraw the whole screen to `out`, which should be a terminal.
+	pub fn draw<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		let mut lines = Vec::new();
+		match self.imu {
+			Some(ref imu) => lines.push(imu.clone()),
+			None => lines.push("waiting for IMU".to_string()),
+		}
+		// Until the first sample there's nothing to draw or scale by.
+		if self.imu.is_some() {
+			for (axis, history) in ["x", "y", "z"].iter().zip(&self.gyro) {
+				let (bars, min, max) = sparkline(history);
+				lines.push(format!("gyro {}  {:<width$}  {:+7.1} to {:+7.1}", axis, bars,
+					DegreesPerSecond(min), DegreesPerSecond(max), width = HISTORY));
+			}
+			for (axis, history) in ["x", "y", "z"].iter().zip(&self.accel) {
+				let (bars, min, max) = sparkline(history);
+				lines.push(format!("accel {} {:<width$}  {:+7.3} to {:+7.3}", axis, bars,
+					Gs(min), Gs(max), width = HISTORY));
+			}
+		}
+		lines.push(String::new());
+		if let Some((roll, pitch, ref text)) = self.attitude {
+			for row in horizon(roll, pitch) {
+				lines.push(format!("|{}|", row));
+			}
+			lines.push(text.clone());
+		}
+		lines.push(String::new());
+		lines.extend(self.airspeed.clone());
+		match self.host {
+			Some(ref host) => lines.push(host.clone()),
+			None => lines.push("host status not read yet".to_string()),
+		}
+		lines.push("battery not monitored".to_string());
+		lines.push(String::new());
+		lines.push("log:".to_string());
+		lines.extend(self.log.iter().cloned());
+
+		// Go to the top left and overwrite what's there, clearing the
+		// rest of each line, rather than clearing the whole screen
+		// first and making it flicker.
+		try!(out.write_all(b"\x1b[H"));
+		for line in lines {
+			try!(write!(out, "{}\x1b[K\n", line));
+		}
+		try!(out.write_all(b"\x1b[J"));
+		out.flush()
+	}
+}
+
+impl Write for Monitor {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.partial.extend_from_slice(buf);
+		while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+			let line: Vec<u8> = self.partial.drain(..end + 1).collect();
+			if self.log.len() == LOG_LINES {
+				self.log.pop_front();
+			}
+			self.log.push_back(String::from_utf8_lossy(&line[..end]).into_owned());
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sparkline_spans_its_range() {
+		let history = (0..8).map(|i| i as f32).collect();
+		assert_eq!(sparkline(&history), ("▁▂▃▄▅▆▇█".to_string(), 0.0, 7.0));
+	}
+
+	#[test]
+	fn flat_sparkline_sits_in_the_middle() {
+		let history = vec![1.0; 3].into_iter().collect();
+		assert_eq!(sparkline(&history), ("▅▅▅".to_string(), 1.0, 1.0));
+	}
+
+	#[test]
+	fn level_horizon_splits_the_view() {
+		let rows = horizon(Radians(0.0), Radians(0.0));
+		assert!(rows[..HORIZON_HEIGHT / 2].iter().all(|row| !row.contains(':')));
+		assert!(rows[HORIZON_HEIGHT / 2 + 1..].iter().all(|row| row.chars().all(|c| c == ':')));
+	}
+
+	#[test]
+	fn horizon_follows_roll_and_pitch() {
+		// Rolled right, the ground comes up on the right.
+		let rows = horizon(Degrees(30.0).into(), Radians(0.0));
+		let above = &rows[HORIZON_HEIGHT / 2 - 2];
+		assert!(above.starts_with(' ') && above.ends_with(':'));
+
+		// Nose up, which is negative pitch, the horizon drops below the
+		// wings.
+		let rows = horizon(Radians(0.0), Degrees(-2.0 * DEGREES_PER_ROW.0).into());
+		assert!(!rows[HORIZON_HEIGHT / 2 + 1].contains(':'));
+		assert!(rows[HORIZON_HEIGHT / 2 + 3].chars().all(|c| c == ':'));
+	}
+
+	#[test]
+	fn log_keeps_the_latest_lines() {
+		let mut monitor = Monitor::new();
+		for i in 0..LOG_LINES + 2 {
+			write!(monitor, "line ").unwrap();
+			writeln!(monitor, "{}", i).unwrap();
+		}
+		write!(monitor, "unfinished").unwrap();
+		assert_eq!(monitor.log.len(), LOG_LINES);
+		assert_eq!(monitor.log.front().unwrap(), "line 2");
+		assert_eq!(monitor.log.back().unwrap(), &format!("line {}", LOG_LINES + 1));
+	}
+}
@@ -0,0 +1,227 @@
+//! Driver for the MS4525DO differential pressure sensor, as used in
+//! pitot-tube airspeed kits.
+
+use color;
+use i2cdev::core::*;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use std::thread::sleep;
+use units::{Celsius, MetersPerSecond, Pascals};
+
+/// Default I2C address of the MS4525DO (the "I" interface type).
+pub const ADDRESS: u16 = 0x28;
+
+/// Air density at sea level in kg/m^3, used to convert dynamic
+/// pressure into indicated airspeed.
+const AIR_DENSITY: f32 = 1.225;
+
+/// One PSI in pascals.
+const PSI_TO_PA: f32 = 6894.757;
+
+/// Zero-offset of the pressure sensor, measured at boot while there is
+/// no airflow through the pitot tube.
+#[derive(Debug)]
+pub struct Calibration {
+	/// Differential pressure read with no airflow
+	pub offset: Pascals,
+}
+
+/// Structure to hold airspeed measurements in real units.
+#[derive(Debug)]
+pub struct AirspeedSample {
+	/// Differential pressure, with the zero-offset removed
+	pub pressure: Pascals,
+	/// Temperature
+	pub temp: Celsius,
+	/// Indicated airspeed
+	pub airspeed: MetersPerSecond,
+	/// True if the pressure read at the limit of the sensor's range, in
+	/// which case the true airspeed may be higher than reported
+	pub clipped: bool,
+}
+
+impl fmt::Display for AirspeedSample {
+	/// Show the sample in aligned columns. A pressure that clipped is
+	/// shown in red when formatted with `{:#}`.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		try!(write!(f, "airspeed {:5.1}  pressure", self.airspeed));
+		try!(color::write_flagged(f, &format!(" {:+7.1}", self.pressure), self.clipped));
+		write!(f, "  temp {:5.1}", self.temp)
+	}
+}
+
+/// Decode the raw differential pressure (in pascals) and temperature (in
+/// degrees Celsius) from the four bytes the sensor returns, along with
+/// whether the pressure count was pinned at either end of its range, or
+/// `None` if the sensor hadn't finished a new conversion since the last
+/// read.
+fn decode(buf: &[u8; 4]) -> io::Result<Option<(f32, f32, bool)>> {
+	// The top two bits of the first byte are a status code: 0 means the
+	// data is fresh, 2 means we read faster than the sensor converts,
+	// and 3 means a fault. 1 is reserved, so treat it as a fault too.
+	match buf[0] >> 6 {
+		0 => {}
+		2 => return Ok(None),
+		_ => return Err(io::Error::new(io::ErrorKind::Other, "MS4525DO reported a fault")),
+	}
+
+	// Pressure is a 14-bit count where 10% and 90% of full scale
+	// correspond to -1 and +1 PSI. Temperature is an 11-bit count
+	// spanning -50 to 150 degrees Celsius.
+	let pressure = (((buf[0] & 0x3f) as u16) << 8) | buf[1] as u16;
+	let temp = ((buf[2] as u16) << 3) | (buf[3] >> 5) as u16;
+	let psi = (pressure as f32 - 0.1 * 16383.0) * 2.0 / (0.8 * 16383.0) - 1.0;
+	let clipped = pressure == 0 || pressure == 0x3fff;
+	Ok(Some((psi * PSI_TO_PA, (temp as f32) * 200.0 / 2047.0 - 50.0, clipped)))
+}
+
+/// Read a measurement from the sensor and `decode` it.
+fn read_raw<E: Error + From<io::Error>>(bus: &mut I2CDevice<Error=E>) -> Result<Option<(f32, f32, bool)>, E> {
+	// Reading from the sensor returns the result of the last
+	// conversion and starts a new one.
+	let mut buf = [0u8; 4];
+	try!(bus.read(&mut buf));
+	Ok(try!(decode(&buf)))
+}
+
+/// Measure the sensor's zero-offset by averaging `count` readings. The
+/// pitot tube must be shielded from wind while this runs.
+pub fn calibrate<E: Error + From<io::Error>>(bus: &mut I2CDevice<Error=E>, count: u32) -> Result<Calibration, E> {
+	average(count, || {
+		// The sensor converts roughly every 2ms, so back off a
+		// little between reads rather than collecting stale data.
+		sleep(Duration::from_millis(10));
+		read_raw(bus)
+	})
+}
+
+/// Average the pressure from `count` fresh readings returned by `read`,
+/// skipping stale ones.
+fn average<E: From<io::Error>, F: FnMut() -> Result<Option<(f32, f32, bool)>, E>>(count: u32, mut read: F) -> Result<Calibration, E> {
+	// An average of nothing would be NaN, which would then turn every
+	// airspeed reading into NaN too.
+	if count == 0 {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "MS4525DO calibration needs at least one reading").into());
+	}
+	let mut total = 0.0;
+	let mut taken = 0;
+	// A wedged sensor, or some other chip at this address, may never
+	// report fresh data, so give up rather than hang at startup.
+	for _ in 0..count.saturating_mul(10) {
+		if taken == count {
+			break;
+		}
+		if let Some((pressure, _, _)) = try!(read()) {
+			total += pressure;
+			taken += 1;
+		}
+	}
+	if taken < count {
+		return Err(io::Error::new(io::ErrorKind::TimedOut, "MS4525DO never reported fresh data").into());
+	}
+	Ok(Calibration { offset: Pascals(total / count as f32) })
+}
+
+/// Read an `AirspeedSample` from the given I2C device, correcting for
+/// the zero-offset measured by `calibrate`.
+pub fn read_sample<E: Error + From<io::Error>>(bus: &mut I2CDevice<Error=E>, cal: &Calibration) -> Result<AirspeedSample, E> {
+	let (pressure, temp, clipped) = match try!(read_raw(bus)) {
+		Some(raw) => raw,
+		None => return Err(io::Error::new(io::ErrorKind::WouldBlock, "MS4525DO data is stale").into()),
+	};
+	let pressure = pressure - cal.offset.0;
+
+	// Bernoulli's equation gives dynamic pressure as 1/2 rho v^2. The
+	// pitot tube may be plumbed either way round, and noise can push
+	// the reading slightly negative at rest, so use the magnitude.
+	Ok(AirspeedSample {
+		pressure: Pascals(pressure),
+		temp: Celsius(temp),
+		airspeed: MetersPerSecond((2.0 * pressure.abs() / AIR_DENSITY).sqrt()),
+		clipped: clipped,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The bytes the sensor would return for the given status code and
+	/// pressure and temperature counts.
+	fn reading(status: u8, pressure: u16, temp: u16) -> [u8; 4] {
+		[(status << 6) | (pressure >> 8) as u8, pressure as u8, (temp >> 3) as u8, (temp << 5) as u8]
+	}
+
+	fn decoded(pressure: u16, temp: u16) -> (f32, f32, bool) {
+		decode(&reading(0, pressure, temp)).unwrap().unwrap()
+	}
+
+	/// One count of pressure, in pascals.
+	const COUNT: f32 = 2.0 * PSI_TO_PA / (0.8 * 16383.0);
+
+	#[test]
+	fn pressure_endpoints() {
+		// 10% and 90% of full scale aren't whole counts, so allow for
+		// rounding to the nearest one.
+		let (low, _, _) = decoded(1638, 0);
+		assert!((low + PSI_TO_PA).abs() < COUNT, "10% count gave {} Pa", low);
+		let (high, _, _) = decoded(14745, 0);
+		assert!((high - PSI_TO_PA).abs() < COUNT, "90% count gave {} Pa", high);
+		let (mid, _, _) = decoded(8192, 0);
+		assert!(mid.abs() < COUNT, "50% count gave {} Pa", mid);
+	}
+
+	#[test]
+	fn temperature_endpoints() {
+		let (_, cold, _) = decoded(8192, 0);
+		assert!((cold + 50.0).abs() < 1e-3, "count 0 gave {} C", cold);
+		let (_, hot, _) = decoded(8192, 2047);
+		assert!((hot - 150.0).abs() < 1e-3, "count 2047 gave {} C", hot);
+	}
+
+	#[test]
+	fn clipped_only_at_the_rails() {
+		assert!(decoded(0, 0).2);
+		assert!(decoded(0x3fff, 0).2);
+		assert!(!decoded(1, 0).2);
+		assert!(!decoded(0x3ffe, 0).2);
+	}
+
+	#[test]
+	fn stale_reading() {
+		assert!(decode(&reading(2, 8192, 1024)).unwrap().is_none());
+	}
+
+	#[test]
+	fn fault_status() {
+		assert!(decode(&reading(1, 8192, 1024)).is_err());
+		assert!(decode(&reading(3, 8192, 1024)).is_err());
+	}
+
+	#[test]
+	fn calibration_averages_fresh_readings() {
+		let mut reads = vec![reading(0, 8192, 0), reading(2, 0, 0), reading(0, 8194, 0)].into_iter();
+		let cal = average(2, || decode(&reads.next().unwrap())).unwrap();
+		let (expected, _, _) = decoded(8193, 0);
+		assert!((cal.offset.0 - expected).abs() < 1e-3, "offset {:?}", cal.offset);
+	}
+
+	#[test]
+	fn calibration_gives_up_on_stale_data() {
+		let mut reads = 0;
+		let result = average(5, || {
+			reads += 1;
+			decode(&reading(2, 8192, 0))
+		});
+		assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+		assert_eq!(reads, 50);
+	}
+
+	#[test]
+	fn calibration_needs_a_reading() {
+		let result = average(0, || decode(&reading(0, 8192, 0)));
+		assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	}
+}
@@ -0,0 +1,95 @@
+//! Where this program writes its measurements.
+
+use libc;
+use monitor::Monitor;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Either standard output, a log file that can be reopened after it
+/// has been rotated, or a full-screen monitor on standard output.
+pub struct Output {
+	path: Option<PathBuf>,
+	out: Box<Write>,
+	color: bool,
+	monitor: Option<Monitor>,
+}
+
+fn open_log(path: &PathBuf) -> io::Result<File> {
+	OpenOptions::new().append(true).create(true).open(path)
+}
+
+impl Output {
+	/// Write to standard output.
+	pub fn stdout() -> Output {
+		// Only use color if someone is watching, not when we're piped
+		// into another program or a file.
+		let color = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+		Output { path: None, out: Box::new(io::stdout()), color: color, monitor: None }
+	}
+
+	/// Draw a full-screen monitor on standard output. Text written to
+	/// the output goes to the monitor's log pane, and values passed to
+	/// `show` are left to the monitor's own panes.
+	pub fn monitor() -> Output {
+		Output { path: None, out: Box::new(io::stdout()), color: true, monitor: Some(Monitor::new()) }
+	}
+
+	/// Append to the log file at `path`, creating it if necessary.
+	pub fn log<P: Into<PathBuf>>(path: P) -> io::Result<Output> {
+		let path = path.into();
+		let file = try!(open_log(&path));
+		Ok(Output { path: Some(path), out: Box::new(file), color: false, monitor: None })
+	}
+
+	/// Close and reopen the log file, so that after a tool like
+	/// logrotate has renamed it we start writing to a fresh file.
+	pub fn reopen(&mut self) -> io::Result<()> {
+		if let Some(ref path) = self.path {
+			try!(self.out.flush());
+			self.out = Box::new(try!(open_log(path)));
+		}
+		Ok(())
+	}
+
+	/// Write `value` on a line of its own, with ANSI color highlighting
+	/// if we're writing to a terminal. Does nothing when drawing a
+	/// monitor, which has its own panes for values.
+	pub fn show<T: Display>(&mut self, value: &T) -> io::Result<()> {
+		if self.monitor.is_some() {
+			Ok(())
+		} else if self.color {
+			writeln!(self, "{:#}", value)
+		} else {
+			writeln!(self, "{}", value)
+		}
+	}
+
+	/// The monitor being drawn, if any, for recording values to show.
+	pub fn monitor_mut(&mut self) -> Option<&mut Monitor> {
+		self.monitor.as_mut()
+	}
+
+	/// Redraw the monitor, if we're drawing one.
+	pub fn redraw(&mut self) -> io::Result<()> {
+		match self.monitor {
+			Some(ref monitor) => monitor.draw(&mut self.out),
+			None => Ok(()),
+		}
+	}
+}
+
+impl Write for Output {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self.monitor {
+			Some(ref mut monitor) => monitor.write(buf),
+			None => self.out.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.out.flush()
+	}
+}
@@ -0,0 +1,81 @@
+//! Overheat detection with hysteresis, so that a temperature hovering
+//! around a limit doesn't produce a stream of alternating warnings.
+
+use units::Celsius;
+
+/// Tracks whether a component is over its temperature limit.
+pub struct Overheat {
+	limit: Celsius,
+	hysteresis: Celsius,
+	hot: bool,
+}
+
+/// A change in a component's overheat state.
+#[derive(Debug, PartialEq)]
+pub enum Transition {
+	/// The temperature rose above the limit
+	Overheated,
+	/// The temperature fell back below the limit minus the hysteresis
+	Recovered,
+}
+
+impl Overheat {
+	/// Warn above `limit`, and consider the component cool again only
+	/// once it drops below `limit - hysteresis`.
+	pub fn new(limit: Celsius, hysteresis: Celsius) -> Overheat {
+		Overheat { limit: limit, hysteresis: hysteresis, hot: false }
+	}
+
+	/// Feed in a new temperature reading, returning the transition if
+	/// this reading changed the overheat state.
+	pub fn update(&mut self, temp: Celsius) -> Option<Transition> {
+		if !self.hot && temp > self.limit {
+			self.hot = true;
+			Some(Transition::Overheated)
+		} else if self.hot && temp < self.limit - self.hysteresis {
+			self.hot = false;
+			Some(Transition::Recovered)
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn no_transition_below_limit() {
+		let mut overheat = Overheat::new(Celsius(85.0), Celsius(5.0));
+		assert_eq!(overheat.update(Celsius(20.0)), None);
+		assert_eq!(overheat.update(Celsius(85.0)), None);
+	}
+
+	#[test]
+	fn overheats_once_when_crossing_limit() {
+		let mut overheat = Overheat::new(Celsius(85.0), Celsius(5.0));
+		assert_eq!(overheat.update(Celsius(85.5)), Some(Transition::Overheated));
+		assert_eq!(overheat.update(Celsius(90.0)), None);
+		assert_eq!(overheat.update(Celsius(86.0)), None);
+	}
+
+	#[test]
+	fn no_recovery_inside_hysteresis_band() {
+		let mut overheat = Overheat::new(Celsius(85.0), Celsius(5.0));
+		overheat.update(Celsius(90.0));
+		assert_eq!(overheat.update(Celsius(84.0)), None);
+		assert_eq!(overheat.update(Celsius(80.0)), None);
+		// Going back over the limit isn't a new overheat either.
+		assert_eq!(overheat.update(Celsius(86.0)), None);
+	}
+
+	#[test]
+	fn recovers_below_limit_minus_hysteresis() {
+		let mut overheat = Overheat::new(Celsius(85.0), Celsius(5.0));
+		overheat.update(Celsius(90.0));
+		assert_eq!(overheat.update(Celsius(79.5)), Some(Transition::Recovered));
+		assert_eq!(overheat.update(Celsius(70.0)), None);
+		assert_eq!(overheat.update(Celsius(85.5)), Some(Transition::Overheated));
+	}
+}
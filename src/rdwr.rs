@@ -0,0 +1,128 @@
+//! Register access on Linux I2C buses using the I2C_RDWR ioctl, which
+//! performs several messages as one combined transaction.
+
+use libc;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use RegisterBus;
+
+/// The i2c-dev ioctl for combined transactions, from linux/i2c-dev.h.
+const I2C_RDWR: libc::c_ulong = 0x0707;
+
+/// Message flag marking a read rather than a write, from linux/i2c.h.
+const I2C_M_RD: u16 = 0x0001;
+
+/// The longest message i2c-dev accepts in an I2C_RDWR transaction.
+const MAX_MESSAGE_LEN: usize = 8192;
+
+/// One message in a combined transaction, matching `struct i2c_msg`.
+#[repr(C)]
+struct Message {
+	addr: u16,
+	flags: u16,
+	len: u16,
+	buf: *mut u8,
+}
+
+/// The ioctl argument, matching `struct i2c_rdwr_ioctl_data`.
+#[repr(C)]
+struct Transaction {
+	msgs: *mut Message,
+	nmsgs: u32,
+}
+
+/// A device at a fixed address on a Linux I2C bus, such as address
+/// 0x68 on `/dev/i2c-1`.
+pub struct Device {
+	file: File,
+	address: u16,
+}
+
+impl Device {
+	/// Open the bus at `path` for talking to the device at `address`.
+	pub fn open<P: AsRef<Path>>(path: P, address: u16) -> io::Result<Device> {
+		let file = try!(OpenOptions::new().read(true).write(true).open(path));
+		Ok(Device { file: file, address: address })
+	}
+
+	/// Run `msgs` as a single transaction, with a repeated start
+	/// between messages instead of a stop.
+	fn transfer(&mut self, msgs: &mut [Message]) -> io::Result<()> {
+		let mut transaction = Transaction {
+			msgs: msgs.as_mut_ptr(),
+			nmsgs: msgs.len() as u32,
+		};
+		// The request argument is a c_ulong on glibc but a c_int on
+		// musl, so let the cast pick whichever this target uses.
+		let result = unsafe {
+			libc::ioctl(self.file.as_raw_fd(), I2C_RDWR as _, &mut transaction as *mut Transaction)
+		};
+		if result < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+}
+
+/// Convert a buffer length to the 16-bit length an I2C message carries,
+/// rejecting buffers longer than i2c-dev allows, which it would
+/// otherwise only refuse with a bare EINVAL.
+fn message_len(len: usize) -> io::Result<u16> {
+	if len > MAX_MESSAGE_LEN {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "I2C message longer than 8192 bytes"));
+	}
+	Ok(len as u16)
+}
+
+impl RegisterBus for Device {
+	type Error = io::Error;
+
+	fn write(&mut self, data: &[u8]) -> io::Result<()> {
+		let len = try!(message_len(data.len()));
+		// The kernel doesn't write through this pointer for messages
+		// without the read flag.
+		self.transfer(&mut [Message {
+			addr: self.address,
+			flags: 0,
+			len: len,
+			buf: data.as_ptr() as *mut u8,
+		}])
+	}
+
+	fn read_reg(&mut self, reg: u8, buf: &mut [u8]) -> io::Result<()> {
+		// Send the register number and read the result back without
+		// releasing the bus in between, so no other master can
+		// address the device and move its register pointer.
+		let len = try!(message_len(buf.len()));
+		let mut reg = [reg];
+		self.transfer(&mut [
+			Message {
+				addr: self.address,
+				flags: 0,
+				len: 1,
+				buf: reg.as_mut_ptr(),
+			},
+			Message {
+				addr: self.address,
+				flags: I2C_M_RD,
+				len: len,
+				buf: buf.as_mut_ptr(),
+			},
+		])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn message_len_limit() {
+		assert_eq!(message_len(0).unwrap(), 0);
+		assert_eq!(message_len(MAX_MESSAGE_LEN).unwrap(), 8192);
+		assert_eq!(message_len(MAX_MESSAGE_LEN + 1).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+		assert_eq!(message_len(65536).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	}
+}
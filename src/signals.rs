@@ -0,0 +1,64 @@
+//! Handling for the signals that ask this program to exit or to reopen
+//! its log file.
+
+use libc;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static EXIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+static REOPEN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn exit_handler(_: libc::c_int) {
+	// Only async-signal-safe work is allowed here, so just set a flag
+	// for the main loop to notice on its next pass.
+	EXIT_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn reopen_handler(_: libc::c_int) {
+	REOPEN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Returns true if `signal` was set to be ignored before we started, as
+/// `nohup` does for SIGHUP and a shell does for SIGINT in background
+/// jobs.
+fn ignored(signal: libc::c_int) -> bool {
+	unsafe {
+		let mut old: libc::sigaction = mem::zeroed();
+		libc::sigaction(signal, ptr::null(), &mut old) == 0 && old.sa_sigaction == libc::SIG_IGN
+	}
+}
+
+/// Run `handler` when `signal` arrives, unless whoever started us asked
+/// for it to be ignored.
+fn handle(signal: libc::c_int, handler: extern "C" fn(libc::c_int)) {
+	if !ignored(signal) {
+		unsafe {
+			libc::signal(signal, handler as libc::sighandler_t);
+		}
+	}
+}
+
+/// Install handlers for SIGINT and SIGTERM, so that Ctrl-C or a service
+/// manager stopping us lets the main loop shut down cleanly instead of
+/// killing the process mid-transaction. SIGHUP asks us to reopen our log
+/// file after it has been rotated if `reopen_on_hangup` is set, and is
+/// otherwise a hangup of our terminal, which should stop us too. A signal
+/// that was ignored when we started is left ignored, so that `nohup` and
+/// background jobs behave as their users expect.
+pub fn install(reopen_on_hangup: bool) {
+	handle(libc::SIGINT, exit_handler);
+	handle(libc::SIGTERM, exit_handler);
+	handle(libc::SIGHUP, if reopen_on_hangup { reopen_handler } else { exit_handler });
+}
+
+/// Returns true once a signal asking us to exit has been received.
+pub fn exit_requested() -> bool {
+	EXIT_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Returns true if a request to reopen the log file has been received
+/// since the last call.
+pub fn take_reopen_request() -> bool {
+	REOPEN_REQUESTED.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,100 @@
+//! Wrapper types for physical quantities, so that mixing up units (such
+//! as g's and meters/second^2) is a type error instead of a subtle bug.
+
+use std::fmt;
+use std::ops::Sub;
+
+/// Standard gravity, in meters/second^2.
+const STANDARD_GRAVITY: f32 = 9.80665;
+
+macro_rules! unit {
+	($(#[$attr:meta])* $name:ident, $symbol:expr) => {
+		$(#[$attr])*
+		#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+		pub struct $name(pub f32);
+
+		impl fmt::Display for $name {
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				// Pass the formatter through so that width and
+				// precision apply to the number.
+				try!(fmt::Display::fmt(&self.0, f));
+				write!(f, " {}", $symbol)
+			}
+		}
+	}
+}
+
+unit!(
+	/// Acceleration in multiples of standard gravity.
+	Gs, "g");
+unit!(
+	/// Acceleration in meters/second^2.
+	MetersPerSecond2, "m/s^2");
+unit!(
+	/// Angle in radians. All angles are kept in radians internally;
+	/// use `Degrees` only when reading or showing values for people.
+	Radians, "rad");
+unit!(
+	/// Angle in degrees.
+	Degrees, "deg");
+unit!(
+	/// Rotational velocity in radians/second. All rates are kept in
+	/// radians/second internally; use `DegreesPerSecond` only when
+	/// reading or showing values for people.
+	RadiansPerSecond, "rad/s");
+unit!(
+	/// Rotational velocity in degrees/second.
+	DegreesPerSecond, "deg/s");
+unit!(
+	/// Temperature in degrees Celsius.
+	Celsius, "C");
+unit!(
+	/// Pressure in pascals.
+	Pascals, "Pa");
+unit!(
+	/// Speed in meters/second.
+	MetersPerSecond, "m/s");
+
+impl From<Gs> for MetersPerSecond2 {
+	fn from(g: Gs) -> MetersPerSecond2 {
+		MetersPerSecond2(g.0 * STANDARD_GRAVITY)
+	}
+}
+
+impl From<MetersPerSecond2> for Gs {
+	fn from(a: MetersPerSecond2) -> Gs {
+		Gs(a.0 / STANDARD_GRAVITY)
+	}
+}
+
+impl From<Degrees> for Radians {
+	fn from(a: Degrees) -> Radians {
+		Radians(a.0.to_radians())
+	}
+}
+
+impl From<Radians> for Degrees {
+	fn from(a: Radians) -> Degrees {
+		Degrees(a.0.to_degrees())
+	}
+}
+
+impl From<DegreesPerSecond> for RadiansPerSecond {
+	fn from(r: DegreesPerSecond) -> RadiansPerSecond {
+		RadiansPerSecond(r.0.to_radians())
+	}
+}
+
+impl From<RadiansPerSecond> for DegreesPerSecond {
+	fn from(r: RadiansPerSecond) -> DegreesPerSecond {
+		DegreesPerSecond(r.0.to_degrees())
+	}
+}
+
+impl Sub for Celsius {
+	type Output = Celsius;
+
+	fn sub(self, other: Celsius) -> Celsius {
+		Celsius(self.0 - other.0)
+	}
+}
@@ -0,0 +1,40 @@
+//! Support for the Linux watchdog device, which resets the board if the
+//! main loop stops running.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+/// An open handle on a watchdog device such as `/dev/watchdog`. Once
+/// opened, the kernel starts the watchdog timer, and the board will be
+/// reset unless `pet` is called more often than the timer's timeout.
+pub struct Watchdog {
+	file: File,
+}
+
+impl Watchdog {
+	/// Open and arm the watchdog device at `path`.
+	pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Watchdog> {
+		let file = try!(OpenOptions::new().write(true).open(path));
+		Ok(Watchdog { file: file })
+	}
+
+	/// Reset the watchdog timer. This must be called once per pass of
+	/// the main loop.
+	pub fn pet(&mut self) -> io::Result<()> {
+		self.file.write_all(b"\0")
+	}
+
+	/// Disarm the watchdog, for use during a clean shutdown. Writing the
+	/// magic character 'V' before closing the device tells the kernel
+	/// to stop the timer.
+	pub fn close(mut self) -> io::Result<()> {
+		self.file.write_all(b"V")
+	}
+}
+
+// There is deliberately no Drop implementation that disarms the
+// watchdog: if we exit because of an error or a panic, the handle is
+// closed without the magic disarm sequence and the kernel keeps the
+// timer running, so the board is reset into a known state.